@@ -1,9 +1,13 @@
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
+use glob::Pattern;
+use rayon::prelude::*;
+use regex::Regex;
 use serde::Serialize;
 
 use structopt::StructOpt;
@@ -19,10 +23,94 @@ struct Arguments {
     blacklist: Vec<String>,
     #[structopt(short)]
     whitelist: Vec<String>,
+    #[structopt(long, default_value = "natural")]
+    sort: SortOrder,
+    /// Treat whitelist/blacklist entries as regular expressions instead of globs.
+    #[structopt(long)]
+    regex: bool,
     #[structopt(subcommand)]
     operation: Operation,
 }
 
+/// A compiled whitelist/blacklist: plain extensions (the common case) are
+/// kept in a `HashSet` for an O(1) lookup, while anything containing a
+/// wildcard (or every entry, in `--regex` mode) is compiled once into a glob
+/// or regex matcher and evaluated against the full filename.
+struct PatternSet {
+    exact_extensions: HashSet<String>,
+    globs: Vec<Pattern>,
+    regexes: Vec<Regex>,
+}
+
+impl PatternSet {
+    fn new(patterns: HashSet<String>, use_regex: bool) -> Result<Self, String> {
+        let mut exact_extensions = HashSet::new();
+        let mut globs = Vec::new();
+        let mut regexes = Vec::new();
+        for pattern in patterns {
+            if use_regex {
+                let regex = Regex::new(&pattern)
+                    .map_err(|error| format!("invalid regex pattern '{}': {}", pattern, error))?;
+                regexes.push(regex);
+            } else if is_wildcard_pattern(&pattern) {
+                let glob = Pattern::new(&pattern)
+                    .map_err(|error| format!("invalid glob pattern '{}': {}", pattern, error))?;
+                globs.push(glob);
+            } else {
+                exact_extensions.insert(pattern.to_lowercase());
+            }
+        }
+        Ok(PatternSet {
+            exact_extensions,
+            globs,
+            regexes,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.exact_extensions.is_empty() && self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    fn matches(&self, file: &Path) -> bool {
+        if self.exact_extensions.contains(&get_extension_str(file)) {
+            return true;
+        }
+        let file_name = file_name_str(file);
+        self.globs.iter().any(|pattern| pattern.matches(&file_name))
+            || self.regexes.iter().any(|regex| regex.is_match(&file_name))
+    }
+}
+
+fn is_wildcard_pattern(pattern: &str) -> bool {
+    pattern
+        .chars()
+        .any(|character| matches!(character, '*' | '?' | '[' | ']'))
+}
+
+#[derive(Clone, Copy)]
+enum SortOrder {
+    Name,
+    Natural,
+    Mtime,
+    Size,
+    Extension,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "name" => Ok(SortOrder::Name),
+            "natural" => Ok(SortOrder::Natural),
+            "mtime" => Ok(SortOrder::Mtime),
+            "size" => Ok(SortOrder::Size),
+            "extension" => Ok(SortOrder::Extension),
+            other => Err(format!("unknown sort order '{}'", other)),
+        }
+    }
+}
+
 #[derive(StructOpt)]
 enum Operation {
     Analyze,
@@ -33,147 +121,528 @@ enum Operation {
         output_directory: PathBuf,
         #[structopt(short = "t")]
         template: String,
+        /// Print the planned source -> dest mapping without touching disk.
+        #[structopt(long)]
+        dry_run: bool,
+        /// Move files into the output directory instead of copying them.
+        #[structopt(long = "move")]
+        move_files: bool,
+        /// Collect per-file failures into a report and keep going instead of
+        /// aborting the whole batch on the first error.
+        #[structopt(long)]
+        skip_errors: bool,
     },
 }
 
 #[derive(Serialize)]
 struct TemplateContext {
     number: usize,
+    index: String,
+    original_name: String,
+    stem: String,
+    extension: String,
+    parent: String,
+    size: u64,
 }
 
-fn ls(path: &Path) -> Vec<PathBuf> {
-    fs::read_dir(path)
-        .unwrap()
-        .map(|e| e.unwrap().path())
-        .collect()
+fn build_template_context(
+    input_directory: &Path,
+    file: &Path,
+    number: usize,
+    zero_padded_index: usize,
+    index_width: usize,
+) -> TemplateContext {
+    let original_name = file.file_name().unwrap().to_string_lossy().to_string();
+    let stem = file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let parent = file
+        .parent()
+        .and_then(|parent| parent.strip_prefix(input_directory).ok())
+        .map(|parent| parent.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let size = fs::metadata(file)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    TemplateContext {
+        number,
+        index: format!("{:0width$}", zero_padded_index, width = index_width),
+        original_name,
+        stem,
+        extension: raw_extension_str(file),
+        parent,
+        size,
+    }
 }
 
-fn ls_recursive(path: &Path, level: usize) -> Vec<PathBuf> {
+fn ls(path: &Path) -> std::io::Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
-    for path in ls(path) {
-        paths.push(path.clone());
-        if path.is_dir() && level > 0 {
-            paths.append(&mut ls_recursive(&path, level - 1));
+    for entry in fs::read_dir(path)? {
+        paths.push(entry?.path());
+    }
+    Ok(paths)
+}
+
+/// The result of a recursive walk: the entries found, plus any directory that
+/// could not be read (its subtree is simply missing from `paths`).
+struct WalkResult {
+    paths: Vec<PathBuf>,
+    errors: Vec<(PathBuf, std::io::Error)>,
+}
+
+fn ls_recursive(path: &Path, level: usize) -> WalkResult {
+    let entries = match ls(path) {
+        Ok(entries) => entries,
+        Err(error) => {
+            return WalkResult {
+                paths: Vec::new(),
+                errors: vec![(path.to_path_buf(), error)],
+            }
+        }
+    };
+    entries
+        .into_par_iter()
+        .map(|entry| {
+            let mut result = WalkResult {
+                paths: vec![entry.clone()],
+                errors: Vec::new(),
+            };
+            if entry.is_dir() && level > 0 {
+                let child = ls_recursive(&entry, level - 1);
+                result.paths.extend(child.paths);
+                result.errors.extend(child.errors);
+            }
+            result
+        })
+        .reduce(
+            || WalkResult {
+                paths: Vec::new(),
+                errors: Vec::new(),
+            },
+            |mut a, b| {
+                a.paths.extend(b.paths);
+                a.errors.extend(b.errors);
+                a
+            },
+        )
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum NaturalChunk {
+    Text(String),
+    Number(u64),
+}
+
+fn natural_chunks(name: &str) -> Vec<NaturalChunk> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_is_digit = false;
+    for character in name.chars() {
+        let is_digit = character.is_ascii_digit();
+        if !current.is_empty() && is_digit != current_is_digit {
+            chunks.push(finish_natural_chunk(&current, current_is_digit));
+            current.clear();
+        }
+        current.push(character);
+        current_is_digit = is_digit;
+    }
+    if !current.is_empty() {
+        chunks.push(finish_natural_chunk(&current, current_is_digit));
+    }
+    chunks
+}
+
+fn finish_natural_chunk(chunk: &str, is_digit: bool) -> NaturalChunk {
+    if is_digit {
+        NaturalChunk::Number(chunk.parse().unwrap_or(0))
+    } else {
+        NaturalChunk::Text(chunk.to_string())
+    }
+}
+
+fn file_name_str(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn sort_paths(paths: &mut [PathBuf], sort: SortOrder) {
+    match sort {
+        SortOrder::Name => paths.sort_by_cached_key(|path| file_name_str(path)),
+        SortOrder::Natural => {
+            paths.sort_by_cached_key(|path| natural_chunks(&file_name_str(path)))
+        }
+        SortOrder::Mtime => paths.sort_by_cached_key(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        }),
+        SortOrder::Size => paths.sort_by_cached_key(|path| {
+            fs::metadata(path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0)
+        }),
+        SortOrder::Extension => paths.sort_by_cached_key(|path| get_extension_str(path)),
+    }
+}
+
+const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "image",
+        &["png", "jpg", "jpeg", "webp", "gif", "bmp", "svg"],
+    ),
+    ("video", &["mp4", "mkv", "mov", "avi", "webm"]),
+    ("audio", &["mp3", "flac", "wav", "ogg", "m4a"]),
+    ("document", &["pdf", "docx", "doc", "txt", "md"]),
+];
+
+fn category_for_extension(extension: &str) -> Option<&'static str> {
+    CATEGORIES
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&extension))
+        .map(|(category, _)| *category)
+}
+
+fn extensions_for_category(category: &str) -> Option<&'static [&'static str]> {
+    CATEGORIES
+        .iter()
+        .find(|(name, _)| *name == category)
+        .map(|(_, extensions)| *extensions)
+}
+
+/// Expands any category names (e.g. "image") in `entries` to their member
+/// extensions, leaving literal extensions untouched, so whitelist/blacklist
+/// matching downstream never has to know about categories.
+fn expand_categories(entries: HashSet<String>) -> HashSet<String> {
+    entries
+        .iter()
+        .flat_map(|entry| match extensions_for_category(entry) {
+            Some(extensions) => extensions
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+            None => vec![entry.clone()],
+        })
+        .collect()
+}
+
+const TOP_DIRECTORIES_COUNT: usize = 5;
+
+/// Walks `path` depth-first, folding each file's size into the cumulative size
+/// of every directory that contains it (a post-order fold: children are sized
+/// before their parent adds them in), and returns the subtree's total size.
+fn directory_sizes(path: &Path, level: usize, sizes: &mut HashMap<PathBuf, u64>) -> u64 {
+    let mut subtree_size: u64 = 0;
+    for entry in ls(path).unwrap() {
+        if entry.is_dir() {
+            if level > 0 {
+                subtree_size += directory_sizes(&entry, level - 1, sizes);
+            }
+        } else {
+            subtree_size += fs::metadata(&entry)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
         }
     }
-    paths
+    sizes.insert(path.to_path_buf(), subtree_size);
+    subtree_size
 }
 
 fn get_extension_str(path: &Path) -> String {
+    raw_extension_str(path).to_lowercase()
+}
+
+fn raw_extension_str(path: &Path) -> String {
     match path.extension() {
-        Some(extension) => extension.to_str().unwrap().to_string().to_lowercase(),
+        Some(extension) => extension.to_str().unwrap().to_string(),
         None => String::from(""),
     }
 }
 
-fn filter_sum<K, V>(map: &HashMap<K, V>, set: &HashSet<K>) -> V
-where
-    K: std::cmp::Eq,
-    K: std::hash::Hash,
-    V: std::iter::Sum,
-    V: Copy,
-{
-    map.iter()
-        .filter(|entry| set.contains(entry.0))
-        .map(|entry| *entry.1)
-        .sum()
-}
-
 fn analyze(
     directory_path: &Path,
     level: usize,
-    blacklist: HashSet<String>,
-    whitelist: HashSet<String>,
+    blacklist: PatternSet,
+    whitelist: PatternSet,
+    sort: SortOrder,
 ) {
-    let paths = ls_recursive(directory_path, level);
+    let walk = ls_recursive(directory_path, level);
+    if let Some((path, error)) = walk.errors.into_iter().next() {
+        panic!("failed to read directory '{}': {}", path.display(), error);
+    }
+    let mut paths = walk.paths;
+    sort_paths(&mut paths, sort);
     let files = paths
         .iter()
-        .filter(|path| !path.is_dir() && path.extension() != None);
+        .filter(|path| !path.is_dir() && path.extension().is_some());
     let file_count = files.clone().count();
-    let file_types: HashMap<String, usize> =
-        files
-            .clone()
-            .fold(HashMap::default(), |mut accumulator, file| {
-                let extension = get_extension_str(file);
-                *accumulator.entry(extension).or_insert(0) += 1;
-                accumulator
-            });
+    let file_types: HashMap<String, usize> = files
+        .clone()
+        .par_bridge()
+        .fold(HashMap::default, |mut accumulator, file| {
+            let extension = get_extension_str(file);
+            *accumulator.entry(extension).or_insert(0) += 1;
+            accumulator
+        })
+        .reduce(HashMap::default, |mut a, b| {
+            for (extension, count) in b {
+                *a.entry(extension).or_insert(0) += count;
+            }
+            a
+        });
+    let extension_sizes: HashMap<String, u64> = files
+        .clone()
+        .par_bridge()
+        .fold(HashMap::default, |mut accumulator, file| {
+            let extension = get_extension_str(file);
+            let size = fs::metadata(file)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            *accumulator.entry(extension).or_insert(0) += size;
+            accumulator
+        })
+        .reduce(HashMap::default, |mut a, b| {
+            for (extension, size) in b {
+                *a.entry(extension).or_insert(0) += size;
+            }
+            a
+        });
     print!("Detected {} file(s), ", file_count);
     if whitelist.is_empty() {
-        println!("{} in blacklist", filter_sum(&file_types, &blacklist));
+        let matched = files.clone().filter(|file| blacklist.matches(file)).count();
+        println!("{} in blacklist", matched);
     } else {
-        println!("{} in whitelist", filter_sum(&file_types, &whitelist));
+        let matched = files.clone().filter(|file| whitelist.matches(file)).count();
+        println!("{} in whitelist", matched);
     }
     if file_count > 0 {
         println!("File type(s):");
-        for file_type in file_types {
-            println!("\t{} '{}' file(s)", file_type.1, file_type.0)
+        let mut sorted_file_types: Vec<(&String, &usize)> = file_types.iter().collect();
+        sorted_file_types.sort_by(|a, b| a.0.cmp(b.0));
+        for file_type in &sorted_file_types {
+            println!(
+                "\t{} '{}' file(s), {} bytes",
+                file_type.1,
+                file_type.0,
+                extension_sizes.get(file_type.0).copied().unwrap_or(0)
+            )
+        }
+        println!("Categories:");
+        let mut category_counts: HashMap<&str, usize> = HashMap::new();
+        for (extension, count) in &file_types {
+            let category = category_for_extension(extension).unwrap_or("other");
+            *category_counts.entry(category).or_insert(0) += count;
+        }
+        let mut sorted_category_counts: Vec<(&str, usize)> = category_counts.into_iter().collect();
+        sorted_category_counts.sort_by(|a, b| a.0.cmp(b.0));
+        for (category, count) in sorted_category_counts {
+            println!("\t{} '{}' file(s)", count, category)
+        }
+    }
+
+    let mut directory_size_map: HashMap<PathBuf, u64> = HashMap::new();
+    directory_sizes(directory_path, level, &mut directory_size_map);
+    let mut heaviest_directories: BinaryHeap<(u64, PathBuf)> = directory_size_map
+        .into_iter()
+        .map(|(path, size)| (size, path))
+        .collect();
+    println!(
+        "Largest director{}:",
+        if TOP_DIRECTORIES_COUNT == 1 {
+            "y"
+        } else {
+            "ies"
+        }
+    );
+    for _ in 0..TOP_DIRECTORIES_COUNT {
+        match heaviest_directories.pop() {
+            Some((size, path)) => println!("\t{} bytes in '{}'", size, path.display()),
+            None => break,
+        }
+    }
+}
+
+/// Moves or copies `source` to `dest`. Moving tries the fast rename-in-place
+/// path first, falling back to copy-then-remove across filesystem boundaries.
+fn transfer_file(source: &Path, dest: &Path, move_files: bool) -> std::io::Result<()> {
+    if move_files {
+        match fs::rename(source, dest) {
+            Ok(()) => Ok(()),
+            Err(_) => fs::copy(source, dest)
+                .and_then(|_| fs::remove_file(source))
+                .map(|_| ()),
         }
+    } else {
+        fs::copy(source, dest).map(|_| ())
     }
 }
 
+struct RenameOptions {
+    start_number: usize,
+    template: String,
+    sort: SortOrder,
+    dry_run: bool,
+    move_files: bool,
+    skip_errors: bool,
+}
+
 fn rename(
     input_directory: &Path,
     level: usize,
-    blacklist: HashSet<String>,
-    whitelist: HashSet<String>,
+    blacklist: PatternSet,
+    whitelist: PatternSet,
     output_directory: &Path,
-    start_number: usize,
-    template: String,
+    options: RenameOptions,
 ) {
-    if output_directory.exists() {
-        fs::remove_dir_all(&output_directory).unwrap();
+    let RenameOptions {
+        start_number,
+        template,
+        sort,
+        dry_run,
+        move_files,
+        skip_errors,
+    } = options;
+    if !dry_run {
+        let prepare_output_directory = || -> std::io::Result<()> {
+            if output_directory.exists() {
+                fs::remove_dir_all(output_directory)?;
+            }
+            fs::create_dir_all(output_directory)
+        };
+        if let Err(error) = prepare_output_directory() {
+            if skip_errors {
+                eprintln!(
+                    "Failed to prepare output directory '{}': {}",
+                    output_directory.display(),
+                    error
+                );
+                std::process::exit(1);
+            }
+            panic!(
+                "failed to prepare output directory '{}': {}",
+                output_directory.display(),
+                error
+            );
+        }
     }
-    fs::create_dir_all(&output_directory).unwrap();
-    let mut number = start_number;
     let mut tiny_template = TinyTemplate::new();
     tiny_template.add_template("rename", &template).unwrap();
-    let files: Vec<PathBuf> = ls_recursive(input_directory, level)
-        .iter()
+    let walk = ls_recursive(input_directory, level);
+    if !walk.errors.is_empty() && !skip_errors {
+        let (path, error) = &walk.errors[0];
+        panic!("failed to read directory '{}': {}", path.display(), error);
+    }
+    let mut files: Vec<PathBuf> = walk
+        .paths
+        .into_iter()
         .filter(|path| !path.is_dir())
-        .map(|path| path.clone())
-        .collect();
-    for file in files {
-        let extension = get_extension_str(&file);
-        if extension.is_empty() {
-            continue;
-        } else if whitelist.is_empty() {
-            if blacklist.contains(&extension) {
-                continue;
+        .filter(|file| {
+            if get_extension_str(file).is_empty() {
+                return false;
             }
-        } else {
-            if !whitelist.contains(&extension) {
-                continue;
+            if whitelist.is_empty() {
+                !blacklist.matches(file)
+            } else {
+                whitelist.matches(file)
             }
+        })
+        .collect();
+    sort_paths(&mut files, sort);
+    let mut failures: Vec<(PathBuf, std::io::Error)> = walk.errors;
+    // Numbers are assigned, and the (single-threaded, !Sync) template is
+    // rendered, against the fully materialised file list before fanning the
+    // actual transfers out across the thread pool, since parallel dispatch
+    // cannot guarantee the order numbers were historically incremented in.
+    let index_width = files.len().to_string().len().max(1);
+    let planned_transfers: Vec<(PathBuf, PathBuf)> = files
+        .into_iter()
+        .enumerate()
+        .map(|(index, file)| {
+            let number = start_number + index;
+            let context =
+                build_template_context(input_directory, &file, number, index, index_width);
+            let dest = output_directory.join(tiny_template.render("rename", &context).unwrap());
+            (file, dest)
+        })
+        .collect();
+    if dry_run {
+        for (file, dest) in &planned_transfers {
+            println!("{} -> {}", file.display(), dest.display());
         }
-        let context = TemplateContext { number: number };
-        fs::copy(
-            file,
-            &output_directory.join(tiny_template.render("rename", &context).unwrap()),
-        )
-        .unwrap();
-        number += 1;
+        if !failures.is_empty() {
+            eprintln!("Failed to read {} director(y/ies):", failures.len());
+            for (path, error) in &failures {
+                eprintln!("\t{}: {}", path.display(), error);
+            }
+        }
+        return;
+    }
+    let transfer_failures: Vec<(PathBuf, std::io::Error)> = planned_transfers
+        .into_par_iter()
+        .filter_map(|(file, dest)| match transfer_file(&file, &dest, move_files) {
+            Ok(()) => None,
+            Err(error) if skip_errors => Some((file, error)),
+            Err(error) => panic!("failed to process '{}': {}", file.display(), error),
+        })
+        .collect();
+    failures.extend(transfer_failures);
+    if !failures.is_empty() {
+        eprintln!("Failed to process {} file(s):", failures.len());
+        for (file, error) in &failures {
+            eprintln!("\t{}: {}", file.display(), error);
+        }
+        std::process::exit(1);
     }
 }
 
 fn main() {
     let arguments = Arguments::from_args();
-    let blacklist: HashSet<String> = arguments.blacklist.into_iter().collect();
-    let whitelist: HashSet<String> = arguments.whitelist.into_iter().collect();
+    let blacklist = PatternSet::new(
+        expand_categories(arguments.blacklist.into_iter().collect()),
+        arguments.regex,
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    let whitelist = PatternSet::new(
+        expand_categories(arguments.whitelist.into_iter().collect()),
+        arguments.regex,
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
     match arguments.operation {
-        Operation::Analyze => analyze(&arguments.directory, arguments.level, blacklist, whitelist),
+        Operation::Analyze => analyze(
+            &arguments.directory,
+            arguments.level,
+            blacklist,
+            whitelist,
+            arguments.sort,
+        ),
         Operation::Rename {
             start_number,
             output_directory,
             template,
+            dry_run,
+            move_files,
+            skip_errors,
         } => rename(
             &arguments.directory,
             arguments.level,
             blacklist,
             whitelist,
             &output_directory,
-            start_number,
-            template,
+            RenameOptions {
+                start_number,
+                template,
+                sort: arguments.sort,
+                dry_run,
+                move_files,
+                skip_errors,
+            },
         ),
     };
 }